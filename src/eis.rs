@@ -1,27 +1,108 @@
 use std::collections::HashMap;
-use std::os::fd::AsRawFd;
+use std::os::fd::{AsRawFd, OwnedFd};
 use std::os::unix::net::UnixStream;
 use std::thread;
 use std::time::Duration;
 
-use reis::ei::{self, keyboard::KeyState};
+use reis::ei::{self, button::ButtonState, keyboard::KeyState};
 use reis::PendingRequestResult;
+use xkbcommon::xkb;
 
 use crate::keymap;
+use crate::sources::{Events, Interest, Sources};
+
+/// Keys identifying the fds an `EisConnection` multiplexes over: the EIS context fd, plus
+/// the listening socket and command source that daemon mode registers alongside it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKey {
+    Eis,
+    /// A daemon-mode listening socket, registered once for the life of the daemon.
+    Listener,
+    /// A caller-registered command source (stdin, or a daemon-mode connection's socket).
+    /// Reused across connections, so callers must `deregister_source` between them.
+    Command,
+}
+
+/// How long a single poll waits for activity before the caller gets control back to
+/// do other bookkeeping (matches the timeout the old ad-hoc poll used).
+const POLL_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Keycode and shift level that produce a given Unicode codepoint under the server's xkb keymap.
+type XkbEntry = (u32, u32);
 
-/// Poll the context fd for readability with a 500ms timeout.
-fn poll_readable(context: &ei::Context) -> std::io::Result<bool> {
-    let fd = context.as_raw_fd();
-    let mut pfd = libc::pollfd {
-        fd,
-        events: libc::POLLIN,
-        revents: 0,
+/// Compile the server-provided xkb keymap and build a `codepoint -> (keycode, level)` lookup
+/// by walking every keycode/level combination the keymap defines. When a codepoint is reachable
+/// at more than one (keycode, level), the lowest level wins so plain/shifted keys are preferred
+/// over AltGr ones.
+fn build_xkb_lookup(fd: OwnedFd, size: u32, verbose: bool) -> HashMap<u32, XkbEntry> {
+    let mut lookup = HashMap::new();
+
+    let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+    let keymap = unsafe {
+        xkb::Keymap::new_from_fd(
+            &context,
+            fd,
+            size as usize,
+            xkb::KEYMAP_FORMAT_TEXT_V1,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        )
+    };
+    let keymap = match keymap {
+        Ok(Some(keymap)) => keymap,
+        Ok(None) => {
+            if verbose {
+                eprintln!("ei-type: server xkb keymap failed to compile");
+            }
+            return lookup;
+        }
+        Err(e) => {
+            if verbose {
+                eprintln!("ei-type: server xkb keymap failed to compile: {}", e);
+            }
+            return lookup;
+        }
     };
-    let ret = unsafe { libc::poll(&mut pfd, 1, 500) };
-    if ret < 0 {
-        Err(std::io::Error::last_os_error())
-    } else {
-        Ok(ret > 0)
+
+    // xkb keycodes are evdev keycodes + 8 (the X11 legacy offset).
+    const XKB_EVDEV_OFFSET: u32 = 8;
+
+    for keycode in keymap.min_keycode().raw()..=keymap.max_keycode().raw() {
+        let keycode = xkb::Keycode::new(keycode);
+        let num_levels = keymap.num_levels_for_key(keycode, 0);
+        for level in 0..num_levels {
+            for sym in keymap.key_get_syms_by_level(keycode, 0, level) {
+                let codepoint = xkb::keysym_to_utf32(*sym);
+                if codepoint == 0 {
+                    continue;
+                }
+                let entry = (keycode.raw().saturating_sub(XKB_EVDEV_OFFSET), level);
+                lookup
+                    .entry(codepoint)
+                    .and_modify(|existing: &mut XkbEntry| {
+                        if entry.1 < existing.1 {
+                            *existing = entry;
+                        }
+                    })
+                    .or_insert(entry);
+            }
+        }
+    }
+
+    if verbose {
+        eprintln!("ei-type: built xkb lookup with {} codepoints", lookup.len());
+    }
+
+    lookup
+}
+
+/// Translate an xkb shift level (0..=3) into the modifiers that select it: level 1 is Shift,
+/// level 2 is AltGr, level 3 is Shift+AltGr. Matches the level ordering most layouts use.
+fn level_modifiers(level: u32) -> (bool, bool) {
+    match level {
+        1 => (true, false),
+        2 => (false, true),
+        3 => (true, true),
+        _ => (false, false),
     }
 }
 
@@ -31,6 +112,37 @@ pub struct EisConnection {
     device: ei::Device,
     last_serial: u32,
     verbose: bool,
+    layout: keymap::Layout,
+    xkb_lookup: HashMap<u32, XkbEntry>,
+    sources: Sources<SourceKey>,
+    events: Events<SourceKey>,
+    pointer: Option<ei::Pointer>,
+    pointer_absolute: Option<ei::PointerAbsolute>,
+    button: Option<ei::Button>,
+    scroll: Option<ei::Scroll>,
+    /// Keycodes currently pressed-and-latched via `press`, not yet released.
+    held: Vec<u32>,
+}
+
+impl Drop for EisConnection {
+    fn drop(&mut self) {
+        let _ = self.release_all();
+    }
+}
+
+// Evdev button codes (linux/input-event-codes.h)
+pub const BTN_LEFT: u32 = 0x110;
+pub const BTN_RIGHT: u32 = 0x111;
+pub const BTN_MIDDLE: u32 = 0x112;
+
+/// Parse a button name ("left", "right", "middle") into its evdev button code.
+pub fn parse_button(name: &str) -> Result<u32, String> {
+    match name.to_lowercase().as_str() {
+        "left" => Ok(BTN_LEFT),
+        "right" => Ok(BTN_RIGHT),
+        "middle" => Ok(BTN_MIDDLE),
+        other => Err(format!("unknown button '{}'", other)),
+    }
 }
 
 impl EisConnection {
@@ -41,6 +153,7 @@ impl EisConnection {
         stream: UnixStream,
         name: &str,
         verbose: bool,
+        layout: keymap::Layout,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         let context = ei::Context::new(stream)?;
 
@@ -89,11 +202,20 @@ impl EisConnection {
         let mut seat_caps: HashMap<String, u64> = HashMap::new();
         let mut keyboard: Option<ei::Keyboard> = None;
         let mut kbd_device: Option<ei::Device> = None;
+        let mut pointer: Option<ei::Pointer> = None;
+        let mut pointer_absolute: Option<ei::PointerAbsolute> = None;
+        let mut button: Option<ei::Button> = None;
+        let mut scroll: Option<ei::Scroll> = None;
         let mut device_interfaces: HashMap<String, reis::Object> = HashMap::new();
+        let mut keymap_info: Option<(OwnedFd, u32)> = None;
         let mut ready = false;
         let mut timeout_count = 0;
         let max_timeouts = 10;
 
+        let mut sources = Sources::new();
+        sources.register(SourceKey::Eis, &context, Interest::READ);
+        let mut events = Events::new();
+
         while !ready && timeout_count < max_timeouts {
             // First drain any already-buffered events (handshake may have read extra data)
             let mut had_events = false;
@@ -175,6 +297,38 @@ impl EisConnection {
                                     kbd_device = Some(device.clone());
                                 }
                             }
+                            if let Some(obj) = device_interfaces.get("ei_pointer") {
+                                if let Some(p) = obj.clone().downcast::<ei::Pointer>() {
+                                    if verbose {
+                                        eprintln!("ei-type: pointer device found");
+                                    }
+                                    pointer = Some(p);
+                                }
+                            }
+                            if let Some(obj) = device_interfaces.get("ei_pointer_absolute") {
+                                if let Some(p) = obj.clone().downcast::<ei::PointerAbsolute>() {
+                                    if verbose {
+                                        eprintln!("ei-type: absolute pointer device found");
+                                    }
+                                    pointer_absolute = Some(p);
+                                }
+                            }
+                            if let Some(obj) = device_interfaces.get("ei_button") {
+                                if let Some(b) = obj.clone().downcast::<ei::Button>() {
+                                    if verbose {
+                                        eprintln!("ei-type: button device found");
+                                    }
+                                    button = Some(b);
+                                }
+                            }
+                            if let Some(obj) = device_interfaces.get("ei_scroll") {
+                                if let Some(s) = obj.clone().downcast::<ei::Scroll>() {
+                                    if verbose {
+                                        eprintln!("ei-type: scroll device found");
+                                    }
+                                    scroll = Some(s);
+                                }
+                            }
                         }
                         ei::device::Event::Resumed { serial } => {
                             last_serial = serial;
@@ -187,11 +341,19 @@ impl EisConnection {
                         }
                         _ => {}
                     },
-                    ei::Event::Keyboard(_kb, ref _evt) => {
-                        if verbose {
-                            eprintln!("ei-type: keyboard event (keymap etc.)");
+                    ei::Event::Keyboard(_kb, evt) => match evt {
+                        ei::keyboard::Event::Keymap { keymap_type: _, size, keymap: fd } => {
+                            if verbose {
+                                eprintln!("ei-type: received xkb keymap, size={}", size);
+                            }
+                            keymap_info = Some((fd, size));
                         }
-                    }
+                        _ => {
+                            if verbose {
+                                eprintln!("ei-type: keyboard event (modifiers etc.)");
+                            }
+                        }
+                    },
                     _ => {}
                 }
             }
@@ -209,18 +371,14 @@ impl EisConnection {
             }
 
             // No pending events — poll for new data
-            match poll_readable(&context) {
-                Ok(true) => {
-                    context.read()?;
-                }
-                Ok(false) => {
-                    timeout_count += 1;
-                    if verbose {
-                        eprintln!("ei-type: poll timeout {}/{}", timeout_count, max_timeouts);
-                    }
+            sources.wait_timeout(&mut events, POLL_TIMEOUT)?;
+            if events.iter().any(|e| e.key == SourceKey::Eis && e.readable) {
+                context.read()?;
+            } else {
+                timeout_count += 1;
+                if verbose {
+                    eprintln!("ei-type: poll timeout {}/{}", timeout_count, max_timeouts);
                 }
-                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
-                Err(e) => return Err(e.into()),
             }
         }
 
@@ -237,27 +395,47 @@ impl EisConnection {
         // Drain any remaining events (keymap fds, other device resumed, etc.)
         // Non-blocking: just process what's already buffered
         while let Some(result) = context.pending_event() {
-            if let PendingRequestResult::Request(ei::Event::Connection(
-                _,
-                ei::connection::Event::Ping { ping },
-            )) = result
-            {
-                ping.done(0);
+            match result {
+                PendingRequestResult::Request(ei::Event::Connection(
+                    _,
+                    ei::connection::Event::Ping { ping },
+                )) => ping.done(0),
+                PendingRequestResult::Request(ei::Event::Keyboard(
+                    _,
+                    ei::keyboard::Event::Keymap { keymap_type: _, size, keymap: fd },
+                )) => keymap_info = Some((fd, size)),
+                _ => {}
             }
         }
         // Try one more read in case there's data on the socket
         let _ = context.read();
         while let Some(result) = context.pending_event() {
-            if let PendingRequestResult::Request(ei::Event::Connection(
-                _,
-                ei::connection::Event::Ping { ping },
-            )) = result
-            {
-                ping.done(0);
+            match result {
+                PendingRequestResult::Request(ei::Event::Connection(
+                    _,
+                    ei::connection::Event::Ping { ping },
+                )) => ping.done(0),
+                PendingRequestResult::Request(ei::Event::Keyboard(
+                    _,
+                    ei::keyboard::Event::Keymap { keymap_type: _, size, keymap: fd },
+                )) => keymap_info = Some((fd, size)),
+                _ => {}
             }
         }
         let _ = context.flush();
 
+        // Compile the server's xkb keymap (if we got one) into a codepoint lookup so
+        // type_text can reach any character the active layout can produce, not just US ASCII.
+        let xkb_lookup = match keymap_info {
+            Some((fd, size)) => build_xkb_lookup(fd, size, verbose),
+            None => {
+                if verbose {
+                    eprintln!("ei-type: no xkb keymap received, using built-in layout only");
+                }
+                HashMap::new()
+            }
+        };
+
         if verbose {
             eprintln!("ei-type: ready to type");
         }
@@ -268,9 +446,49 @@ impl EisConnection {
             device,
             last_serial,
             verbose,
+            layout,
+            xkb_lookup,
+            sources,
+            events,
+            pointer,
+            pointer_absolute,
+            button,
+            scroll,
+            held: Vec::new(),
         })
     }
 
+    /// Register an additional fd to be polled alongside the EIS context fd.
+    /// Used by daemon mode to multiplex a command socket onto the same loop.
+    pub fn register_source(&mut self, key: SourceKey, fd: &impl AsRawFd, interest: Interest) {
+        self.sources.register(key, fd, interest);
+    }
+
+    /// Stop polling every fd registered under `key`. Call this before reusing a key
+    /// for a different fd (e.g. a daemon's `SourceKey::Command` between connections),
+    /// so the old, possibly-closed fd isn't left in the poll set.
+    pub fn deregister_source(&mut self, key: SourceKey) {
+        self.sources.unregister(&key);
+    }
+
+    /// Poll every registered source for up to `timeout` and process any EIS events that
+    /// arrived. Returns the sources that reported activity, so a daemon loop can react
+    /// to its own fds (command socket, etc.) as well.
+    pub fn poll_sources(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Vec<(SourceKey, bool, bool)>, Box<dyn std::error::Error>> {
+        self.sources.wait_timeout(&mut self.events, timeout)?;
+        let mut ready = Vec::new();
+        for event in self.events.iter() {
+            ready.push((event.key, event.readable, event.hangup || event.error));
+            if event.key == SourceKey::Eis && event.readable {
+                self.dispatch();
+            }
+        }
+        Ok(ready)
+    }
+
     /// Process any pending incoming events (e.g. pings from the server).
     fn dispatch(&self) {
         let _ = self.context.read();
@@ -292,8 +510,11 @@ impl EisConnection {
         delay_us: u64,
     ) -> Result<(), Box<dyn std::error::Error>> {
         for c in text.chars() {
-            if let Some(ki) = keymap::char_to_key(c) {
-                self.type_key(ki.code, ki.shift, delay_us)?;
+            if let Some(&(code, level)) = self.xkb_lookup.get(&(c as u32)) {
+                let (shift, altgr) = level_modifiers(level);
+                self.type_key(code, shift, altgr, delay_us)?;
+            } else if let Some(lk) = self.layout.char_to_key(c) {
+                self.type_key(lk.code, lk.shift, lk.altgr, delay_us)?;
             } else if self.verbose {
                 eprintln!("ei-type: skipping unmapped char '{}'", c.escape_debug());
             }
@@ -301,33 +522,48 @@ impl EisConnection {
         Ok(())
     }
 
-    /// Send a key combo like "ctrl+v" or "enter".
+    /// Send a key combo like "ctrl+v" or "enter", held for `delay_us` before release.
     pub fn send_key_combo(
         &mut self,
         combo: &str,
         delay_us: u64,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let (modifiers, keycode) = keymap::parse_combo(combo)?;
+        self.tap(combo, delay_us)
+    }
+
+    /// Press a combo's modifiers and key, leaving them latched (tracked in `held`)
+    /// across calls until a matching `release` or `release_all`.
+    pub fn press(&mut self, combo: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let (modifiers, keycode) = keymap::parse_combo(&self.layout, combo)?;
 
-        // Press modifiers
         for &m in &modifiers {
             self.keyboard.key(m, KeyState::Press);
             self.device.frame(self.last_serial, 0);
+            self.held.push(m);
         }
 
-        // Press and release key
         self.keyboard.key(keycode, KeyState::Press);
         self.device.frame(self.last_serial, 0);
+        self.held.push(keycode);
+
         self.context.flush()?;
-        thread::sleep(Duration::from_micros(delay_us));
+        self.dispatch();
+        Ok(())
+    }
+
+    /// Release a combo previously pressed with `press` (key first, then modifiers
+    /// in reverse), untracking each keycode from `held`.
+    pub fn release(&mut self, combo: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let (modifiers, keycode) = keymap::parse_combo(&self.layout, combo)?;
 
         self.keyboard.key(keycode, KeyState::Released);
         self.device.frame(self.last_serial, 0);
+        self.held.retain(|&c| c != keycode);
 
-        // Release modifiers in reverse
         for &m in modifiers.iter().rev() {
             self.keyboard.key(m, KeyState::Released);
             self.device.frame(self.last_serial, 0);
+            self.held.retain(|&c| c != m);
         }
 
         self.context.flush()?;
@@ -335,16 +571,106 @@ impl EisConnection {
         Ok(())
     }
 
+    /// Press a combo, hold it for `hold_us`, then release it.
+    pub fn tap(&mut self, combo: &str, hold_us: u64) -> Result<(), Box<dyn std::error::Error>> {
+        self.press(combo)?;
+        thread::sleep(Duration::from_micros(hold_us));
+        self.release(combo)
+    }
+
+    /// Release every key currently tracked as held, in reverse press order. Called
+    /// automatically on drop so a crashing caller doesn't leave modifiers stuck down.
+    pub fn release_all(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let held = std::mem::take(&mut self.held);
+        if held.is_empty() {
+            return Ok(());
+        }
+
+        for code in held.into_iter().rev() {
+            self.keyboard.key(code, KeyState::Released);
+            self.device.frame(self.last_serial, 0);
+        }
+
+        self.context.flush()?;
+        self.dispatch();
+        Ok(())
+    }
+
+    /// Move the pointer relative to its current position.
+    pub fn move_rel(&mut self, dx: f32, dy: f32) -> Result<(), Box<dyn std::error::Error>> {
+        let pointer = self.pointer.as_ref().ok_or("no pointer device available")?;
+        pointer.motion_relative(dx, dy);
+        self.device.frame(self.last_serial, 0);
+        self.context.flush()?;
+        self.dispatch();
+        Ok(())
+    }
+
+    /// Move the pointer to an absolute position.
+    pub fn move_abs(&mut self, x: f32, y: f32) -> Result<(), Box<dyn std::error::Error>> {
+        let pointer_absolute = self
+            .pointer_absolute
+            .as_ref()
+            .ok_or("no absolute pointer device available")?;
+        pointer_absolute.motion_absolute(x, y);
+        self.device.frame(self.last_serial, 0);
+        self.context.flush()?;
+        self.dispatch();
+        Ok(())
+    }
+
+    /// Press a button.
+    pub fn press_button(&mut self, code: u32) -> Result<(), Box<dyn std::error::Error>> {
+        let button = self.button.as_ref().ok_or("no button device available")?;
+        button.button(code, ButtonState::Press);
+        self.device.frame(self.last_serial, 0);
+        self.context.flush()?;
+        self.dispatch();
+        Ok(())
+    }
+
+    /// Release a button.
+    pub fn release_button(&mut self, code: u32) -> Result<(), Box<dyn std::error::Error>> {
+        let button = self.button.as_ref().ok_or("no button device available")?;
+        button.button(code, ButtonState::Released);
+        self.device.frame(self.last_serial, 0);
+        self.context.flush()?;
+        self.dispatch();
+        Ok(())
+    }
+
+    /// Press and release a button, e.g. for a single click.
+    pub fn click(&mut self, code: u32, delay_us: u64) -> Result<(), Box<dyn std::error::Error>> {
+        self.press_button(code)?;
+        thread::sleep(Duration::from_micros(delay_us));
+        self.release_button(code)
+    }
+
+    /// Scroll by a relative amount (positive `dy` scrolls down).
+    pub fn scroll(&mut self, dx: f32, dy: f32) -> Result<(), Box<dyn std::error::Error>> {
+        let scroll = self.scroll.as_ref().ok_or("no scroll device available")?;
+        scroll.scroll(dx, dy);
+        self.device.frame(self.last_serial, 0);
+        self.context.flush()?;
+        self.dispatch();
+        Ok(())
+    }
+
     fn type_key(
         &mut self,
         code: u32,
         shift: bool,
+        altgr: bool,
         delay_us: u64,
     ) -> Result<(), Box<dyn std::error::Error>> {
         if shift {
             self.keyboard.key(keymap::KEY_LEFTSHIFT, KeyState::Press);
             self.device.frame(self.last_serial, 0);
         }
+        if altgr {
+            self.keyboard.key(keymap::KEY_RIGHTALT, KeyState::Press);
+            self.device.frame(self.last_serial, 0);
+        }
 
         self.keyboard.key(code, KeyState::Press);
         self.device.frame(self.last_serial, 0);
@@ -354,6 +680,10 @@ impl EisConnection {
         self.keyboard.key(code, KeyState::Released);
         self.device.frame(self.last_serial, 0);
 
+        if altgr {
+            self.keyboard.key(keymap::KEY_RIGHTALT, KeyState::Released);
+            self.device.frame(self.last_serial, 0);
+        }
         if shift {
             self.keyboard.key(keymap::KEY_LEFTSHIFT, KeyState::Released);
             self.device.frame(self.last_serial, 0);
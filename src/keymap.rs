@@ -1,3 +1,9 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
 /// Key information: evdev keycode and whether shift is required.
 pub struct KeyInfo {
     pub code: u32,
@@ -59,6 +65,7 @@ pub const KEY_DOT: u32 = 52;
 pub const KEY_SLASH: u32 = 53;
 pub const KEY_LEFTALT: u32 = 56;
 pub const KEY_SPACE: u32 = 57;
+pub const KEY_RIGHTALT: u32 = 100;
 pub const KEY_LEFTMETA: u32 = 125;
 
 const AZ_CODES: [u32; 26] = [
@@ -115,9 +122,96 @@ pub fn char_to_key(c: char) -> Option<KeyInfo> {
     }
 }
 
-/// Parse a key combo string like "ctrl+v", "enter", "shift+a".
+/// A single entry in a layout table: keycode plus the modifiers needed to produce the char.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LayoutEntry {
+    pub code: u32,
+    #[serde(default)]
+    pub shift: bool,
+    #[serde(default)]
+    pub altgr: bool,
+}
+
+/// Resolved key for a character under a `Layout`: keycode plus which modifiers produce it.
+pub struct LayoutKey {
+    pub code: u32,
+    pub shift: bool,
+    pub altgr: bool,
+}
+
+/// A keyboard layout: maps each character to the keycode (and modifiers) that produces it.
+///
+/// Falls back to the built-in US QWERTY table (`char_to_key`) for any character it
+/// doesn't define, so a layout file only needs to override what differs from US.
+pub struct Layout {
+    table: HashMap<char, LayoutEntry>,
+}
+
+impl Layout {
+    /// The built-in US QWERTY layout, with no overrides.
+    pub fn us() -> Self {
+        Layout { table: HashMap::new() }
+    }
+
+    /// Load a layout from a TOML or JSON file mapping each char to `{ code, shift, altgr }`.
+    /// The format is picked from the file extension (`.json` => JSON, anything else => TOML).
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read layout file {}: {}", path.display(), e))?;
+
+        let raw: HashMap<String, LayoutEntry> = if path.extension().and_then(|e| e.to_str()) == Some("json")
+        {
+            serde_json::from_str(&data)
+                .map_err(|e| format!("failed to parse layout file {} as JSON: {}", path.display(), e))?
+        } else {
+            toml::from_str(&data)
+                .map_err(|e| format!("failed to parse layout file {} as TOML: {}", path.display(), e))?
+        };
+
+        let mut table = HashMap::with_capacity(raw.len());
+        for (key, entry) in raw {
+            let mut chars = key.chars();
+            let c = chars
+                .next()
+                .ok_or_else(|| format!("empty key in layout file {}", path.display()))?;
+            if chars.next().is_some() {
+                return Err(format!("layout key '{}' is not a single character", key));
+            }
+            table.insert(c, entry);
+        }
+
+        Ok(Layout { table })
+    }
+
+    /// Map a character to its keycode and modifiers, consulting the loaded overrides
+    /// before falling back to the built-in US table.
+    pub fn char_to_key(&self, c: char) -> Option<LayoutKey> {
+        if let Some(entry) = self.table.get(&c) {
+            return Some(LayoutKey { code: entry.code, shift: entry.shift, altgr: entry.altgr });
+        }
+        char_to_key(c).map(|ki| LayoutKey { code: ki.code, shift: ki.shift, altgr: false })
+    }
+}
+
+/// Map a modifier name ("ctrl", "shift", ...) to its evdev keycode.
+fn modifier_to_key(name: &str) -> Option<u32> {
+    match name {
+        "ctrl" | "control" => Some(KEY_LEFTCTRL),
+        "shift" => Some(KEY_LEFTSHIFT),
+        "alt" => Some(KEY_LEFTALT),
+        "altgr" => Some(KEY_RIGHTALT),
+        "super" | "meta" => Some(KEY_LEFTMETA),
+        _ => None,
+    }
+}
+
+/// Parse a key combo string like "ctrl+v", "enter", "shift+a". A combo may also be
+/// modifier-only, e.g. "shift" or "ctrl+shift", for holding modifiers across other
+/// calls (see `EisConnection::press`/`release`) — the last modifier becomes the
+/// returned keycode and any earlier ones stay in the modifier list.
+/// Consults `layout` for single-character keys so combos are layout-correct.
 /// Returns (modifier_keycodes, final_keycode).
-pub fn parse_combo(combo: &str) -> Result<(Vec<u32>, u32), String> {
+pub fn parse_combo(layout: &Layout, combo: &str) -> Result<(Vec<u32>, u32), String> {
     let parts: Vec<&str> = combo.split('+').collect();
     if parts.is_empty() {
         return Err("empty combo".into());
@@ -127,25 +221,26 @@ pub fn parse_combo(combo: &str) -> Result<(Vec<u32>, u32), String> {
 
     // All parts except last are modifiers
     for &part in &parts[..parts.len() - 1] {
-        let modifier = match part.to_lowercase().as_str() {
-            "ctrl" | "control" => KEY_LEFTCTRL,
-            "shift" => KEY_LEFTSHIFT,
-            "alt" => KEY_LEFTALT,
-            "super" | "meta" => KEY_LEFTMETA,
-            other => return Err(format!("unknown modifier '{}'", other)),
-        };
+        let name = part.to_lowercase();
+        let modifier = modifier_to_key(&name).ok_or_else(|| format!("unknown modifier '{}'", name))?;
         modifiers.push(modifier);
     }
 
-    // Last part is the key
+    // Last part is the key, unless the whole combo is modifier names (e.g. "shift",
+    // "ctrl+shift"), in which case the last modifier doubles as the held keycode.
     let key_str = parts.last().unwrap().to_lowercase();
-    let keycode = if key_str.len() == 1 {
+    let keycode = if let Some(modifier) = modifier_to_key(&key_str) {
+        modifier
+    } else if key_str.chars().count() == 1 {
         let c = key_str.chars().next().unwrap();
-        let ki = char_to_key(c).ok_or_else(|| format!("unknown key '{}'", c))?;
-        if ki.shift && !modifiers.contains(&KEY_LEFTSHIFT) {
+        let lk = layout.char_to_key(c).ok_or_else(|| format!("unknown key '{}'", c))?;
+        if lk.shift && !modifiers.contains(&KEY_LEFTSHIFT) {
             modifiers.push(KEY_LEFTSHIFT);
         }
-        ki.code
+        if lk.altgr && !modifiers.contains(&KEY_RIGHTALT) {
+            modifiers.push(KEY_RIGHTALT);
+        }
+        lk.code
     } else {
         match key_str.as_str() {
             "enter" | "return" => KEY_ENTER,
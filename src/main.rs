@@ -1,12 +1,17 @@
 mod eis;
 mod keymap;
+mod sources;
 
-use std::io::{self, Read};
-use std::os::unix::net::UnixStream;
+use std::io::{self, BufRead, Read};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
 use std::process;
+use std::time::Duration;
 
 use clap::Parser;
 
+use sources::Interest;
+
 /// Type text into the focused window via KWin EIS + libei
 #[derive(Parser)]
 #[command(name = "ei-type")]
@@ -22,6 +27,59 @@ struct Args {
     /// Verbose debug output
     #[arg(short = 'v', long = "verbose")]
     verbose: bool,
+
+    /// Path to a TOML or JSON layout file mapping char -> { code, shift, altgr }.
+    /// Falls back to the built-in US QWERTY table when not given.
+    #[arg(long = "layout")]
+    layout: Option<PathBuf>,
+
+    /// Stay connected and type line-delimited commands as they arrive, instead of
+    /// exiting after one invocation. Amortizes the D-Bus + libei handshake cost
+    /// across an entire dictation session.
+    #[arg(long = "daemon")]
+    daemon: bool,
+
+    /// In --daemon mode, listen on this Unix socket for commands instead of stdin.
+    #[arg(long = "socket", requires = "daemon")]
+    socket: Option<PathBuf>,
+
+    /// Click a mouse button (left, right, middle)
+    #[arg(long = "click")]
+    click: Option<String>,
+
+    /// Move the pointer by dx,dy pixels relative to its current position
+    #[arg(long = "move", value_name = "DX,DY")]
+    move_rel: Option<String>,
+
+    /// Move the pointer to an absolute x,y position
+    #[arg(long = "move-abs", value_name = "X,Y")]
+    move_abs: Option<String>,
+
+    /// Scroll by dx,dy
+    #[arg(long = "scroll", value_name = "DX,DY")]
+    scroll: Option<String>,
+
+    /// How long to hold a --key combo down before releasing it, in milliseconds.
+    /// Defaults to --delay.
+    #[arg(long = "hold")]
+    hold_ms: Option<u64>,
+
+    /// Press a combo (e.g. shift, ctrl+shift). The keys are released again when this
+    /// invocation exits; only --daemon's `press:`/`release:` keep a combo held across commands.
+    #[arg(long = "press")]
+    press: Option<String>,
+
+    /// Send release events for a combo's key and modifiers
+    #[arg(long = "release")]
+    release: Option<String>,
+}
+
+/// Parse a "DX,DY" CLI argument into a pair of floats.
+fn parse_xy(s: &str) -> Result<(f32, f32), String> {
+    let (x, y) = s.split_once(',').ok_or_else(|| format!("expected DX,DY, got '{}'", s))?;
+    let x: f32 = x.trim().parse().map_err(|_| format!("invalid number '{}'", x))?;
+    let y: f32 = y.trim().parse().map_err(|_| format!("invalid number '{}'", y))?;
+    Ok((x, y))
 }
 
 /// Call KWin's connectToEIS D-Bus method, returning the EIS Unix socket.
@@ -56,6 +114,7 @@ async fn connect_kwin_eis(verbose: bool) -> Result<(UnixStream, zbus::Connection
 async fn main() {
     let args = Args::parse();
     let delay_us = args.delay_ms * 1000;
+    let hold_us = args.hold_ms.map(|ms| ms * 1000).unwrap_or(delay_us);
 
     // Get EIS socket from KWin via D-Bus
     // Keep the D-Bus connection alive — KWin invalidates EIS when D-Bus disconnects
@@ -67,8 +126,20 @@ async fn main() {
         }
     };
 
+    // Load the keyboard layout (falls back to built-in US QWERTY)
+    let layout = match &args.layout {
+        Some(path) => match keymap::Layout::load(path) {
+            Ok(l) => l,
+            Err(e) => {
+                eprintln!("ei-type: failed to load layout: {}", e);
+                process::exit(1);
+            }
+        },
+        None => keymap::Layout::us(),
+    };
+
     // Connect to EIS and negotiate keyboard device
-    let mut eis = match eis::EisConnection::connect(stream, "ei-type", args.verbose) {
+    let mut eis = match eis::EisConnection::connect(stream, "ei-type", args.verbose, layout) {
         Ok(c) => c,
         Err(e) => {
             eprintln!("ei-type: failed to get keyboard device: {}", e);
@@ -76,9 +147,78 @@ async fn main() {
         }
     };
 
+    // Daemon mode: stay connected and type whatever commands stream in
+    if args.daemon {
+        if let Err(e) = run_daemon(eis, delay_us, hold_us, args.socket) {
+            eprintln!("ei-type: daemon failed: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    // Pointer/button verbs
+    if let Some(name) = &args.click {
+        match eis::parse_button(name).map_err(Box::<dyn std::error::Error>::from).and_then(|code| {
+            eis.click(code, delay_us)
+        }) {
+            Ok(()) => return,
+            Err(e) => {
+                eprintln!("ei-type: click failed: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+    if let Some(xy) = &args.move_rel {
+        match parse_xy(xy).map_err(Box::<dyn std::error::Error>::from).and_then(|(dx, dy)| {
+            eis.move_rel(dx, dy)
+        }) {
+            Ok(()) => return,
+            Err(e) => {
+                eprintln!("ei-type: move failed: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+    if let Some(xy) = &args.move_abs {
+        match parse_xy(xy).map_err(Box::<dyn std::error::Error>::from).and_then(|(x, y)| {
+            eis.move_abs(x, y)
+        }) {
+            Ok(()) => return,
+            Err(e) => {
+                eprintln!("ei-type: move-abs failed: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+    if let Some(xy) = &args.scroll {
+        match parse_xy(xy).map_err(Box::<dyn std::error::Error>::from).and_then(|(dx, dy)| {
+            eis.scroll(dx, dy)
+        }) {
+            Ok(()) => return,
+            Err(e) => {
+                eprintln!("ei-type: scroll failed: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+    if let Some(combo) = &args.press {
+        if let Err(e) = eis.press(combo) {
+            eprintln!("ei-type: press failed: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+    if let Some(combo) = &args.release {
+        if let Err(e) = eis.release(combo) {
+            eprintln!("ei-type: release failed: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
     // Key combo mode
     if let Some(combo) = &args.key {
-        if let Err(e) = eis.send_key_combo(combo, delay_us) {
+        if let Err(e) = eis.send_key_combo(combo, hold_us) {
             eprintln!("ei-type: key combo failed: {}", e);
             process::exit(1);
         }
@@ -97,3 +237,115 @@ async fn main() {
         process::exit(1);
     }
 }
+
+/// Run as a persistent daemon: keep `eis` connected and type line-delimited commands
+/// read from stdin, or from a Unix socket when `socket` is given. A bare line is typed
+/// as text, `key:<combo>` sends a key combo (held for `hold_us`), `press:<combo>`/
+/// `release:<combo>` hold and release a combo (e.g. `press:shift` ... `release:shift`
+/// to bracket several commands in Shift), `delay:<ms>` adjusts the inter-key delay,
+/// and `hold:<ms>` adjusts the `key:` hold duration — both live, for everything that
+/// follows.
+fn run_daemon(
+    mut eis: eis::EisConnection,
+    mut delay_us: u64,
+    mut hold_us: u64,
+    socket: Option<PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match socket {
+        Some(path) => {
+            let _ = std::fs::remove_file(&path);
+            let listener = UnixListener::bind(&path)?;
+            eis.register_source(eis::SourceKey::Listener, &listener, Interest::READ);
+            eprintln!("ei-type: daemon listening on {}", path.display());
+
+            loop {
+                wait_for_source(&mut eis, eis::SourceKey::Listener)?;
+                let (stream, _) = listener.accept()?;
+                eis.register_source(eis::SourceKey::Command, &stream, Interest::READ);
+                let mut reader = io::BufReader::new(&stream);
+                let result = run_command_loop(&mut eis, &mut reader, &mut delay_us, &mut hold_us);
+                // Drop the closed connection's fd from the poll set before the next
+                // accept reuses SourceKey::Command, so a recycled fd number can't be
+                // mistaken for this one.
+                eis.deregister_source(eis::SourceKey::Command);
+                result?;
+            }
+        }
+        None => {
+            let stdin = io::stdin();
+            eis.register_source(eis::SourceKey::Command, &stdin, Interest::READ);
+            let mut reader = io::BufReader::new(stdin.lock());
+            run_command_loop(&mut eis, &mut reader, &mut delay_us, &mut hold_us)
+        }
+    }
+}
+
+/// Block, while still answering EIS pings via `poll_sources`, until `key` has data
+/// available or has hung up (so the caller's next read sees EOF).
+fn wait_for_source(
+    eis: &mut eis::EisConnection,
+    key: eis::SourceKey,
+) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        let ready = eis.poll_sources(Duration::from_millis(500))?;
+        if ready.iter().any(|&(k, readable, hangup)| k == key && (readable || hangup)) {
+            return Ok(());
+        }
+    }
+}
+
+/// Read and execute commands from `reader` until it hits EOF (stdin closed, or the
+/// connected client disconnected). Only blocks on `wait_for_source` when `reader`'s
+/// internal buffer is empty — a client that writes several `\n`-terminated commands
+/// in one packet gets all of them drained and executed before the next poll, rather
+/// than stalling on commands `read_line` already has buffered.
+fn run_command_loop<R: Read>(
+    eis: &mut eis::EisConnection,
+    reader: &mut io::BufReader<R>,
+    delay_us: &mut u64,
+    hold_us: &mut u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut line = String::new();
+    loop {
+        if reader.buffer().is_empty() {
+            wait_for_source(eis, eis::SourceKey::Command)?;
+        }
+        line.clear();
+        let n = reader.read_line(&mut line)?;
+        if n == 0 {
+            return Ok(());
+        }
+        if let Err(e) = handle_command(eis, line.trim_end_matches(['\n', '\r']), delay_us, hold_us) {
+            eprintln!("ei-type: command failed: {}", e);
+        }
+    }
+}
+
+/// Execute a single daemon-mode command line.
+fn handle_command(
+    eis: &mut eis::EisConnection,
+    line: &str,
+    delay_us: &mut u64,
+    hold_us: &mut u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(ms) = line.strip_prefix("delay:") {
+        let ms: u64 = ms.trim().parse().map_err(|e| format!("invalid delay '{}': {}", ms, e))?;
+        *delay_us = ms * 1000;
+        return Ok(());
+    }
+    if let Some(ms) = line.strip_prefix("hold:") {
+        let ms: u64 = ms.trim().parse().map_err(|e| format!("invalid hold '{}': {}", ms, e))?;
+        *hold_us = ms * 1000;
+        return Ok(());
+    }
+    if let Some(combo) = line.strip_prefix("key:") {
+        return eis.send_key_combo(combo.trim(), *hold_us);
+    }
+    if let Some(combo) = line.strip_prefix("press:") {
+        return eis.press(combo.trim());
+    }
+    if let Some(combo) = line.strip_prefix("release:") {
+        return eis.release(combo.trim());
+    }
+    eis.type_text(line, *delay_us)
+}
@@ -0,0 +1,130 @@
+//! A small popol-inspired registry of pollable fds, so an event loop can watch an
+//! arbitrary number of sources (the EIS context fd, a command socket, ...) with a
+//! single `libc::poll` call instead of hand-rolling one `pollfd` per caller.
+
+use std::os::fd::AsRawFd;
+use std::time::Duration;
+
+/// Which readiness a registered fd should be watched for.
+#[derive(Debug, Clone, Copy)]
+pub struct Interest {
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Interest {
+    pub const READ: Interest = Interest { read: true, write: false };
+    pub const WRITE: Interest = Interest { read: false, write: true };
+}
+
+/// A registry of fds to poll, each tagged with a caller-chosen `Key` so the result
+/// of `wait_timeout` can be matched back to the source that produced it.
+pub struct Sources<Key> {
+    fds: Vec<libc::pollfd>,
+    keys: Vec<Key>,
+}
+
+impl<Key: Clone> Sources<Key> {
+    pub fn new() -> Self {
+        Sources { fds: Vec::new(), keys: Vec::new() }
+    }
+
+    /// Register an fd under `key` with the given interest. Registering the same
+    /// key again adds a second entry rather than replacing the first — call
+    /// `unregister` first if the key is being reused for a different fd.
+    pub fn register(&mut self, key: Key, fd: &impl AsRawFd, interest: Interest) {
+        let mut events = 0;
+        if interest.read {
+            events |= libc::POLLIN;
+        }
+        if interest.write {
+            events |= libc::POLLOUT;
+        }
+        self.fds.push(libc::pollfd { fd: fd.as_raw_fd(), events, revents: 0 });
+        self.keys.push(key);
+    }
+
+    /// Poll every registered fd once, retrying internally on `EINTR`, and fill
+    /// `events` with one entry per fd that reported activity.
+    pub fn wait_timeout(
+        &mut self,
+        events: &mut Events<Key>,
+        timeout: Duration,
+    ) -> std::io::Result<()> {
+        events.events.clear();
+
+        loop {
+            let ret = unsafe {
+                libc::poll(
+                    self.fds.as_mut_ptr(),
+                    self.fds.len() as libc::nfds_t,
+                    timeout.as_millis().min(i32::MAX as u128) as i32,
+                )
+            };
+            if ret < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            break;
+        }
+
+        for (pfd, key) in self.fds.iter_mut().zip(self.keys.iter()) {
+            if pfd.revents != 0 {
+                events.events.push(Event {
+                    key: key.clone(),
+                    readable: pfd.revents & libc::POLLIN != 0,
+                    writable: pfd.revents & libc::POLLOUT != 0,
+                    hangup: pfd.revents & libc::POLLHUP != 0,
+                    error: pfd.revents & libc::POLLERR != 0,
+                });
+                pfd.revents = 0;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<Key: Clone + PartialEq> Sources<Key> {
+    /// Remove every entry registered under `key`, so a reused key (e.g. a daemon's
+    /// per-connection command socket) doesn't accumulate stale pollfd entries for
+    /// fds the OS has since closed and may recycle.
+    pub fn unregister(&mut self, key: &Key) {
+        let mut i = 0;
+        while i < self.keys.len() {
+            if &self.keys[i] == key {
+                self.keys.remove(i);
+                self.fds.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+/// One fd's readiness, as reported by `Sources::wait_timeout`.
+pub struct Event<Key> {
+    pub key: Key,
+    pub readable: bool,
+    pub writable: bool,
+    pub hangup: bool,
+    pub error: bool,
+}
+
+/// The set of fds that became ready from one `Sources::wait_timeout` call.
+pub struct Events<Key> {
+    events: Vec<Event<Key>>,
+}
+
+impl<Key> Events<Key> {
+    pub fn new() -> Self {
+        Events { events: Vec::new() }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Event<Key>> {
+        self.events.iter()
+    }
+}